@@ -0,0 +1,1498 @@
+pub mod vfs;
+
+use std::path::{self, Path, PathBuf};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use zip::ZipWriter;
+use zip::write::FileOptions;
+
+use vfs::{FileType, Vfs};
+
+/// Files at least this large get their own byte progress bar while being zipped.
+const LARGE_FILE_THRESHOLD: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    NoFiles,
+    OnlyExcludedFiles,
+    NotImageMajority,
+    NonImageFiles,
+    LimitReached,
+    AboveZipDepth,
+    TimeBudgetExceeded,
+    NonUtf8Name,
+    Unreadable,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            SkipReason::NoFiles => "no files",
+            SkipReason::OnlyExcludedFiles => "only excluded files",
+            SkipReason::NotImageMajority => "not mostly images",
+            SkipReason::NonImageFiles => "non-image files in strict mode",
+            SkipReason::LimitReached => "--limit reached",
+            SkipReason::AboveZipDepth => "above --zip-depth",
+            SkipReason::TimeBudgetExceeded => "--max-duration exceeded",
+            SkipReason::NonUtf8Name => "name is not valid UTF-8",
+            SkipReason::Unreadable => "could not be read",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// An archive `compress_images` decided to create, written by `write_archives`.
+struct PendingArchive {
+    dir: String,
+    zip_path: String,
+    files: Vec<PathBuf>,
+    removable_files: Vec<PathBuf>,
+}
+
+/// Options and shared state of one run over the filesystem `fs`.
+pub struct Context {
+    /// Lowercase extensions, without the dot, of files to ignore
+    pub exclude_ext: Vec<String>,
+    pub delete_excluded: bool,
+    pub strict: bool,
+    pub limit: Option<usize>,
+    pub no_recurse: bool,
+    pub zip_depth: Option<usize>,
+    /// Archive directories without checking that they are mostly images
+    pub archive_all: bool,
+    pub deadline: Option<Instant>,
+    fs: Box<dyn Vfs>,
+    archived_count: AtomicUsize,
+    skipped: Mutex<Vec<(String, SkipReason)>>,
+    pending_archives: Mutex<Vec<PendingArchive>>,
+    scanned_dirs: AtomicUsize,
+    scanned_files: AtomicUsize,
+    scan_progress: ProgressBar,
+    multi_progress: MultiProgress,
+}
+
+impl Context {
+    pub fn new(fs: Box<dyn Vfs>, multi_progress: MultiProgress) -> Self {
+        Context {
+            exclude_ext: Vec::new(),
+            delete_excluded: false,
+            strict: false,
+            limit: None,
+            no_recurse: false,
+            zip_depth: None,
+            archive_all: false,
+            deadline: None,
+            fs,
+            archived_count: AtomicUsize::new(0),
+            skipped: Mutex::new(Vec::new()),
+            pending_archives: Mutex::new(Vec::new()),
+            scanned_dirs: AtomicUsize::new(0),
+            scanned_files: AtomicUsize::new(0),
+            scan_progress: ProgressBar::hidden(),
+            multi_progress,
+        }
+    }
+
+    /// Shows a spinner with directory and file counts while the tree is scanned.
+    pub fn show_scan_progress(&mut self) {
+        self.scan_progress = self.multi_progress.add(ProgressBar::new_spinner());
+        self.scan_progress.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} {msg}")
+                .unwrap(),
+        );
+        self.scan_progress
+            .enable_steady_tick(Duration::from_millis(100));
+    }
+
+    /// Prints a line to stdout without garbling the progress bars.
+    fn println(&self, message: impl AsRef<str>) {
+        self.multi_progress
+            .suspend(|| println!("{}", message.as_ref()));
+    }
+
+    /// Prints a line to stderr without garbling the progress bars.
+    fn eprintln(&self, message: impl AsRef<str>) {
+        self.multi_progress
+            .suspend(|| eprintln!("{}", message.as_ref()));
+    }
+
+    pub fn fs(&self) -> &dyn Vfs {
+        self.fs.as_ref()
+    }
+
+    pub fn archived_count(&self) -> usize {
+        self.archived_count.load(Ordering::SeqCst)
+    }
+
+    /// Clears the scan spinner once traversal is over.
+    pub fn finish_scan(&self) {
+        self.scan_progress.finish_and_clear();
+    }
+
+    /// Reserves one of the `--limit` slots, returning false once they are used up.
+    fn try_reserve_archive(&self) -> bool {
+        self.archived_count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                match self.limit {
+                    Some(limit) if count >= limit => None,
+                    _ => Some(count + 1),
+                }
+            })
+            .is_ok()
+    }
+
+    fn skip(&self, dir: &str, reason: SkipReason) {
+        self.skipped.lock().unwrap().push((dir.to_string(), reason));
+    }
+
+    fn record_scanned(&self, files: usize) {
+        let dirs = self.scanned_dirs.fetch_add(1, Ordering::SeqCst) + 1;
+        let files = self.scanned_files.fetch_add(files, Ordering::SeqCst) + files;
+        self.scan_progress
+            .set_message(format!("Scanning: {} directories, {} files", dirs, files));
+    }
+
+    pub fn report_skipped(&self, verbose: bool) {
+        let mut skipped = self.skipped.lock().unwrap();
+        if skipped.is_empty() {
+            return;
+        }
+        skipped.sort();
+
+        println!("Skipped directories: {}", skipped.len());
+        let mut reasons: Vec<_> = skipped.iter().map(|(_, reason)| *reason).collect();
+        reasons.sort();
+        reasons.dedup();
+        for reason in reasons {
+            let count = skipped.iter().filter(|(_, r)| *r == reason).count();
+            println!("  {}: {}", reason, count);
+        }
+
+        if verbose {
+            for (dir, reason) in skipped.iter() {
+                println!("  {} ({})", dir, reason);
+            }
+        }
+    }
+
+    pub fn skipped_for(&self, reason: SkipReason) -> bool {
+        self.skipped
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(_, r)| *r == reason)
+    }
+
+    fn time_budget_exceeded(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub fn limit_reached(&self) -> bool {
+        self.limit
+            .is_some_and(|limit| self.archived_count.load(Ordering::SeqCst) >= limit)
+    }
+}
+
+/// Parses durations like `45s`, `90m`, `2h` or `1h30m`; plain numbers are seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{}', use e.g. 45s, 90m or 2h", value);
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    if value.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let amount: u64 = number.parse().map_err(|_| invalid())?;
+        total = amount
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+pub fn check_if_directory_exists(fs: &dyn Vfs, dir: &str) -> Result<(), String> {
+    let Ok(metadata) = fs.metadata(path::Path::new(dir)) else {
+        return Err(format!("Directory '{}' does not exist", dir));
+    };
+    if metadata.file_type != FileType::Dir {
+        return Err(format!("'{}' is not a directory", dir));
+    }
+    Ok(())
+}
+
+fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Matches `name` against a shell-style pattern supporting `*`, `?`, `[abc]`,
+/// `[a-z]` and `[!abc]`. Wildcards never match a leading dot.
+fn matches_wildcard(pattern: &[char], name: &[char]) -> bool {
+    if name.first() == Some(&'.') && pattern.first() != Some(&'.') {
+        return false;
+    }
+
+    fn match_from(pattern: &[char], name: &[char]) -> bool {
+        match pattern.first() {
+            None => name.is_empty(),
+            Some('*') => (0..=name.len()).any(|i| match_from(&pattern[1..], &name[i..])),
+            Some('?') => !name.is_empty() && match_from(&pattern[1..], &name[1..]),
+            Some('[') => {
+                let Some(close) = pattern.iter().skip(2).position(|c| *c == ']') else {
+                    return name.first() == Some(&'[') && match_from(&pattern[1..], &name[1..]);
+                };
+                let Some(c) = name.first() else {
+                    return false;
+                };
+                let mut class = &pattern[1..close + 2];
+                let negated = matches!(class.first(), Some('!') | Some('^'));
+                if negated {
+                    class = &class[1..];
+                }
+                let mut found = false;
+                let mut i = 0;
+                while i < class.len() {
+                    if i + 2 < class.len() && class[i + 1] == '-' {
+                        found |= class[i] <= *c && *c <= class[i + 2];
+                        i += 3;
+                    } else {
+                        found |= class[i] == *c;
+                        i += 1;
+                    }
+                }
+                found != negated && match_from(&pattern[close + 3..], &name[1..])
+            }
+            Some(p) => name.first() == Some(p) && match_from(&pattern[1..], &name[1..]),
+        }
+    }
+
+    match_from(pattern, name)
+}
+
+/// Expands wildcards in a directory pattern into the matching directories.
+/// Patterns without wildcards, and paths that exist as written such as
+/// `[Group] Series`, are returned unchanged. Wrap a character in brackets to
+/// match it literally, e.g. `[[]Group] *`.
+pub fn expand_dir_pattern(fs: &dyn Vfs, pattern: &str) -> Result<Vec<String>, String> {
+    if !has_wildcards(pattern) || fs.exists(Path::new(pattern)) {
+        return Ok(vec![pattern.to_string()]);
+    }
+
+    let mut candidates = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let part = component.as_os_str().to_string_lossy();
+        if !matches!(component, path::Component::Normal(_)) || !has_wildcards(&part) {
+            for candidate in candidates.iter_mut() {
+                candidate.push(component);
+            }
+            continue;
+        }
+
+        let part: Vec<char> = part.chars().collect();
+        let mut matched = Vec::new();
+        for candidate in &candidates {
+            let search_dir = if candidate.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                candidate.as_path()
+            };
+            let Ok(entries) = fs.read_dir(search_dir) else {
+                continue;
+            };
+            let mut names: Vec<_> = entries
+                .into_iter()
+                .filter(|entry| entry.file_type == FileType::Dir)
+                .filter_map(|entry| entry.path.file_name().map(|name| name.to_os_string()))
+                .collect();
+            names.sort();
+            for name in names {
+                let chars: Vec<char> = name.to_string_lossy().chars().collect();
+                if matches_wildcard(&part, &chars) {
+                    matched.push(candidate.join(name));
+                }
+            }
+        }
+        candidates = matched;
+    }
+
+    let dirs: Vec<_> = candidates
+        .into_iter()
+        .filter(|path| {
+            fs.metadata(path)
+                .is_ok_and(|metadata| metadata.file_type == FileType::Dir)
+        })
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    if dirs.is_empty() {
+        return Err(format!("No directories match '{}'", pattern));
+    }
+    Ok(dirs)
+}
+
+/// Lists every file below `dir`, including those in nested subdirectories.
+fn collect_files_recursively(dir: &Path, ctx: &Context) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut dirents = ctx.fs().read_dir(dir)?;
+    dirents.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name()));
+
+    let mut files = Vec::new();
+    let mut file_count = 0;
+    for entry in dirents {
+        match entry.file_type {
+            FileType::File => {
+                files.push(entry.path);
+                file_count += 1;
+            }
+            FileType::Dir => files.extend(collect_files_recursively(&entry.path, ctx)?),
+            FileType::Other => (),
+        }
+    }
+    ctx.record_scanned(file_count);
+
+    Ok(files)
+}
+
+/// Runs the leaf function on `dir` and returns its files that were not excluded.
+fn process_leaf<F>(
+    dir: &str,
+    file_paths: Vec<PathBuf>,
+    process_leaf_entry_fn: &F,
+    ctx: &Context,
+) -> Result<Vec<PathBuf>, std::io::Error>
+where
+    F: for<'a> Fn(&'a str, &'a [path::PathBuf], &'a Context) -> Result<bool, std::io::Error>,
+{
+    if ctx.time_budget_exceeded() {
+        ctx.skip(dir, SkipReason::TimeBudgetExceeded);
+        return Ok(Vec::new());
+    }
+
+    match process_leaf_entry_fn(dir, &file_paths, ctx) {
+        Ok(_) => Ok(file_paths
+            .into_iter()
+            .filter(|path| !is_excluded_file(path, ctx))
+            .collect()),
+        Err(e) => {
+            ctx.eprintln(format!("Error processing directory {}: {}", dir, e));
+            Err(e)
+        }
+    }
+}
+
+/// Runs `process_leaf_entry_fn` on every directory below `dir` that is to be
+/// archived and returns the files that were processed.
+///
+/// The tree is walked in sorted order, one directory at a time, so limits and
+/// archive names always go to the same directories; only writing the archives
+/// runs in parallel.
+pub fn process_directory<F>(
+    dir: &str,
+    process_leaf_entry_fn: F,
+    ctx: &Context,
+) -> Result<Vec<path::PathBuf>, std::io::Error>
+where
+    F: for<'a> Fn(&'a str, &'a [path::PathBuf], &'a Context) -> Result<bool, std::io::Error>
+        + Send
+        + Sync
+        + Clone,
+{
+    let files = process_directory_recursively(dir, process_leaf_entry_fn, ctx, 0);
+    write_archives(ctx)?;
+    files
+}
+
+fn process_directory_recursively<F>(
+    dir: &str,
+    process_leaf_entry_fn: F,
+    ctx: &Context,
+    depth: usize,
+) -> Result<Vec<path::PathBuf>, std::io::Error>
+where
+    F: for<'a> Fn(&'a str, &'a [path::PathBuf], &'a Context) -> Result<bool, std::io::Error>
+        + Send
+        + Sync
+        + Clone,
+{
+    if ctx.time_budget_exceeded() {
+        ctx.skip(dir, SkipReason::TimeBudgetExceeded);
+        return Ok(Vec::new());
+    }
+
+    if ctx.zip_depth == Some(depth) {
+        let file_paths = match collect_files_recursively(Path::new(dir), ctx) {
+            Ok(file_paths) => file_paths,
+            Err(e) => return skip_unreadable(dir, e, depth, ctx),
+        };
+        return process_leaf(dir, file_paths, &process_leaf_entry_fn, ctx);
+    }
+
+    let mut dirents = match ctx.fs().read_dir(Path::new(dir)) {
+        Ok(dirents) => dirents,
+        Err(e) => return skip_unreadable(dir, e, depth, ctx),
+    };
+    // Sort entries so the same tree is always processed in the same order
+    dirents.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name()));
+    let mut file_paths = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in dirents {
+        match entry.file_type {
+            FileType::File => file_paths.push(entry.path),
+            FileType::Dir => dirs.push(entry),
+            // Broken symlinks, sockets and the like are neither archived nor entered
+            FileType::Other => (),
+        }
+    }
+    ctx.record_scanned(file_paths.len());
+
+    // Nothing above --zip-depth is archived, so report every directory there
+    // that files are left behind in
+    if ctx.zip_depth.is_some() && (dirs.is_empty() || !file_paths.is_empty()) {
+        ctx.skip(dir, SkipReason::AboveZipDepth);
+    }
+    if dirs.is_empty() && ctx.zip_depth.is_some() {
+        return Ok(file_paths
+            .into_iter()
+            .filter(|path| !is_excluded_file(path, ctx))
+            .collect());
+    }
+
+    if dirs.is_empty() || ctx.no_recurse {
+        return process_leaf(dir, file_paths, &process_leaf_entry_fn, ctx);
+    }
+
+    let subdir_files: Vec<_> = dirs
+        .into_iter()
+        .filter_map(|entry| {
+            let Some(path) = entry.path.to_str() else {
+                ctx.skip(&entry.path.to_string_lossy(), SkipReason::NonUtf8Name);
+                return None;
+            };
+            let process_entry = process_leaf_entry_fn.clone();
+            process_directory_recursively(path, process_entry, ctx, depth + 1).ok()
+        })
+        .flatten()
+        .collect();
+
+    Ok(subdir_files)
+}
+
+/// Handles a directory that could not be read. Errors on the root are returned
+/// for the caller to report, those below it are printed and recorded as skips.
+fn skip_unreadable(
+    dir: &str,
+    error: std::io::Error,
+    depth: usize,
+    ctx: &Context,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    if depth == 0 {
+        return Err(error);
+    }
+    ctx.eprintln(format!("Failed to read directory {}: {}", dir, error));
+    ctx.skip(dir, SkipReason::Unreadable);
+    Ok(Vec::new())
+}
+
+pub fn is_image_file(path: &Path) -> bool {
+    if let Some(ext) = path.extension() {
+        let ext = ext.to_string_lossy().to_lowercase();
+        matches!(
+            ext.as_str(),
+            "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tiff" | "avif" | "heic" | "svg"
+        )
+    } else {
+        false
+    }
+}
+
+fn is_excluded_file(path: &Path, ctx: &Context) -> bool {
+    if let Some(ext) = path.extension() {
+        let ext = ext.to_string_lossy().to_lowercase();
+        ctx.exclude_ext.contains(&ext)
+    } else {
+        false
+    }
+}
+
+/// Lists every file below `dir` the same way the traversal does, following symlinks.
+fn files_below(fs: &dyn Vfs, dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut files = Vec::new();
+    for entry in fs.read_dir(dir)? {
+        match entry.file_type {
+            FileType::File => files.push(entry.path),
+            FileType::Dir => files.extend(files_below(fs, &entry.path)?),
+            FileType::Other => (),
+        }
+    }
+    Ok(files)
+}
+
+/// Unlinks a symlinked directory, leaving the files of its target alone, if
+/// every file reached through it is in `files`. Returns whether it did.
+fn remove_symlinked_dir(
+    link: &Path,
+    files: &HashSet<&Path>,
+    ctx: &Context,
+) -> Result<bool, std::io::Error> {
+    let fs = ctx.fs();
+    if !files_below(fs, link)?
+        .iter()
+        .all(|path| files.contains(path.as_path()))
+    {
+        return Ok(false);
+    }
+    // Directory symlinks are files on Unix but directories on Windows
+    fs.remove_file(link).or_else(|_| fs.remove_dir(link))?;
+    Ok(true)
+}
+
+/// Removes the directories below `dir` that hold nothing but other empty
+/// directories, deepest first. Symlinked directories are left alone.
+fn remove_empty_subdirs(fs: &dyn Vfs, dir: &Path) -> Result<(), std::io::Error> {
+    for entry in fs.read_dir(dir)? {
+        if entry.file_type != FileType::Dir || fs.is_symlink(&entry.path) {
+            continue;
+        }
+        remove_empty_subdirs(fs, &entry.path)?;
+        if fs.read_dir(&entry.path)?.is_empty() {
+            fs.remove_dir(&entry.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes `files`, the subdirectories of `dir` that are left empty, and then
+/// `dir` itself if nothing else is left in it. Returns the directories that
+/// were kept because they are not empty.
+///
+/// Files reached through a symlinked directory belong to the link target, so
+/// only the link itself is removed, like `remove_dir_all` does.
+fn remove_leaf_dir(
+    dir: &str,
+    files: &[PathBuf],
+    ctx: &Context,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let fs = ctx.fs();
+    let dir_path = Path::new(dir);
+    let file_set: HashSet<_> = files.iter().map(PathBuf::as_path).collect();
+    if fs.is_symlink(dir_path) {
+        return Ok(match remove_symlinked_dir(dir_path, &file_set, ctx)? {
+            true => Vec::new(),
+            false => vec![dir_path.to_path_buf()],
+        });
+    }
+
+    let mut links = Vec::new();
+    for path in files {
+        let parents: Vec<_> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|ancestor| *ancestor != dir_path)
+            .collect();
+
+        // The outermost symlinked directory the file was reached through, if any
+        if let Some(link) = parents.iter().rev().find(|parent| fs.is_symlink(parent)) {
+            links.push(link.to_path_buf());
+            continue;
+        }
+
+        match fs.remove_file(path) {
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    links.sort();
+    links.dedup();
+    let mut kept = Vec::new();
+    for link in &links {
+        if !remove_symlinked_dir(link, &file_set, ctx)? {
+            kept.push(link.clone());
+        }
+    }
+
+    // Subdirectories are only part of the archive when they were descended into
+    if !ctx.no_recurse {
+        remove_empty_subdirs(fs, dir_path)?;
+    }
+
+    if !fs.read_dir(dir_path)?.is_empty() {
+        kept.push(dir_path.to_path_buf());
+        return Ok(kept);
+    }
+    // `.` and `..` cannot be removed by that name
+    if dir_path.file_name().is_none() {
+        fs.remove_dir(&resolve_path(fs, dir_path)?)?;
+    } else {
+        fs.remove_dir(dir_path)?;
+    }
+    Ok(kept)
+}
+
+fn print_kept_dirs(kept: &[PathBuf], ctx: &Context) {
+    for dir in kept {
+        ctx.println(format!("Keeping non-empty directory: {}", dir.display()));
+    }
+}
+
+/// Makes `path` absolute and resolves `.` and `..` without touching the
+/// filesystem, so paths like `.` get a usable name.
+fn resolve_path(fs: &dyn Vfs, path: &Path) -> Result<PathBuf, std::io::Error> {
+    let mut resolved = PathBuf::new();
+    for component in fs.current_dir()?.join(path).components() {
+        match component {
+            path::Component::ParentDir => {
+                resolved.pop();
+            }
+            path::Component::CurDir => (),
+            _ => resolved.push(component),
+        }
+    }
+    Ok(resolved)
+}
+
+/// Builds the archive entry name of `path`, relative to `base_dir` and using `/`
+/// as the separator.
+fn zip_entry_name(base_dir: &Path, path: &Path) -> Result<String, std::io::Error> {
+    let relative = path.strip_prefix(base_dir).unwrap_or(path);
+    let names: Option<Vec<_>> = relative
+        .components()
+        .filter_map(|component| match component {
+            path::Component::Normal(name) => Some(name.to_str()),
+            _ => None,
+        })
+        .collect();
+    match names {
+        Some(names) if !names.is_empty() => Ok(names.join("/")),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Invalid file name",
+        )),
+    }
+}
+
+/// Zips `files` into `output_path`, naming entries relative to `base_dir`.
+pub fn create_zip(
+    output_path: &str,
+    base_dir: &Path,
+    files: &[PathBuf],
+    ctx: &Context,
+) -> Result<(), std::io::Error> {
+    let multi_progress = &ctx.multi_progress;
+    let temp_path = format!("{}.tmp", output_path);
+
+    let file = ctx.fs().create(Path::new(&temp_path))?;
+    let mut zip = ZipWriter::new(file);
+
+    let pb = multi_progress.add(ProgressBar::new(files.len() as u64));
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} files ({eta}) {msg}")
+        .unwrap()
+        .progress_chars("#>-"));
+
+    let basename = Path::new(output_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(output_path);
+    pb.set_message(format!("Zipping: {}", basename));
+
+    let options = FileOptions::<()>::default().compression_method(zip::CompressionMethod::Deflated);
+
+    // Write files to zip from memory
+
+    for path in files {
+        let file_name = zip_entry_name(base_dir, path)?;
+        pb.set_message(format!("Zipping: {} ({})", basename, file_name));
+        zip.start_file(file_name, options)?;
+        let mut file = ctx.fs().open(path)?;
+        let size = ctx.fs().metadata(path)?.len;
+        if size >= LARGE_FILE_THRESHOLD {
+            let file_pb = multi_progress.insert_after(&pb, ProgressBar::new(size));
+            file_pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("  [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec})")
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+            std::io::copy(&mut file_pb.wrap_read(&mut file), &mut zip)?;
+            file_pb.finish_and_clear();
+        } else {
+            std::io::copy(&mut file, &mut zip)?;
+        }
+
+        pb.inc(1);
+    }
+
+    zip.finish()?;
+    pb.finish_and_clear();
+
+    // Rename the temporary file to the final output path
+    ctx.fs()
+        .rename(Path::new(&temp_path), Path::new(output_path))?;
+
+    Ok(())
+}
+
+/// Returns the deepest directory containing all of `roots`.
+fn common_base_dir(roots: &[String]) -> PathBuf {
+    let mut base: Vec<_> = match roots.first() {
+        Some(root) => Path::new(root).components().collect(),
+        None => return PathBuf::new(),
+    };
+    for root in &roots[1..] {
+        let common = base
+            .iter()
+            .zip(Path::new(root).components())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        base.truncate(common);
+    }
+    base.iter().collect()
+}
+
+/// Writes every image below `roots` into a single archive at `output_path`,
+/// named relative to the directory the roots have in common. The original
+/// files are left in place.
+pub fn create_single_archive(
+    output_path: &str,
+    roots: &[String],
+    ctx: &Context,
+) -> Result<usize, std::io::Error> {
+    if ctx.fs().exists(Path::new(output_path)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", output_path),
+        ));
+    }
+
+    let mut files = Vec::new();
+    for root in roots {
+        files.extend(
+            collect_files_recursively(Path::new(root), ctx)?
+                .into_iter()
+                .filter(|path| is_image_file(path) && !is_excluded_file(path, ctx)),
+        );
+    }
+    files.sort();
+    files.dedup();
+    ctx.finish_scan();
+
+    create_zip(output_path, &common_base_dir(roots), &files, ctx)?;
+
+    Ok(files.len())
+}
+
+/// Decides whether to archive `dir` and reserves a name for its archive. The
+/// archive is written, and `dir` removed, by `write_archives`, which
+/// `process_directory` calls once the whole tree has been walked.
+pub fn compress_images(
+    dir: &str,
+    all_files: &[path::PathBuf],
+    ctx: &Context,
+) -> Result<bool, std::io::Error> {
+    let (excluded_files, files): (Vec<_>, Vec<_>) = all_files
+        .iter()
+        .cloned()
+        .partition(|path| is_excluded_file(path, ctx));
+    let img_files: Vec<_> = files
+        .iter()
+        .filter(|path| is_image_file(path))
+        .cloned()
+        .collect();
+    let other_files: Vec<_> = files
+        .iter()
+        .filter(|path| !is_image_file(path))
+        .cloned()
+        .collect();
+
+    if files.is_empty() {
+        if excluded_files.is_empty() {
+            ctx.skip(dir, SkipReason::NoFiles);
+        } else {
+            ctx.skip(dir, SkipReason::OnlyExcludedFiles);
+        }
+        return Ok(true);
+    }
+
+    // --strict applies in every mode, protecting sidecar files even when packing
+    if ctx.strict && !other_files.is_empty() {
+        ctx.skip(dir, SkipReason::NonImageFiles);
+        return Ok(true);
+    }
+
+    if !ctx.archive_all && img_files.len() <= other_files.len() {
+        ctx.skip(dir, SkipReason::NotImageMajority);
+        return Ok(true);
+    }
+
+    if !ctx.try_reserve_archive() {
+        ctx.skip(dir, SkipReason::LimitReached);
+        return Ok(true);
+    }
+
+    // Resolve paths like `.` so the archive gets a real name next to the directory
+    let resolved_dir;
+    let mut dir_path = path::Path::new(dir);
+    if dir_path.file_name().is_none() {
+        resolved_dir = resolve_path(ctx.fs(), dir_path)?;
+        dir_path = resolved_dir.as_path();
+    }
+    let dir_name = dir_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown");
+
+    let parent_dir = dir_path.parent().and_then(|p| p.to_str()).unwrap_or(".");
+    // Hold the lock while picking a name so no other archive can claim it
+    let mut pending = ctx.pending_archives.lock().unwrap();
+    let mut zip_path = format!("{}/{}.zip", parent_dir, dir_name);
+    let mut counter = 1;
+    // Find a non-conflicting path by adding (1), (2), etc. if needed
+    while ctx.fs().exists(Path::new(&zip_path))
+        || pending.iter().any(|archive| archive.zip_path == zip_path)
+    {
+        zip_path = format!("{}/{}({}).zip", parent_dir, dir_name, counter);
+        counter += 1;
+    }
+
+    // The files to delete once the archive has been written
+    let mut removable_files = files.clone();
+    if ctx.delete_excluded {
+        removable_files.extend(excluded_files);
+    }
+    pending.push(PendingArchive {
+        dir: dir.to_string(),
+        zip_path,
+        files,
+        removable_files,
+    });
+
+    Ok(true)
+}
+
+/// Creates one pending archive and removes the directory it replaces,
+/// returning the directories that were kept.
+fn write_archive(archive: &PendingArchive, ctx: &Context) -> Result<Vec<PathBuf>, std::io::Error> {
+    if ctx.time_budget_exceeded() {
+        ctx.skip(&archive.dir, SkipReason::TimeBudgetExceeded);
+        ctx.archived_count.fetch_sub(1, Ordering::SeqCst);
+        return Ok(Vec::new());
+    }
+
+    if let Err(e) = create_zip(
+        &archive.zip_path,
+        Path::new(&archive.dir),
+        &archive.files,
+        ctx,
+    ) {
+        ctx.archived_count.fetch_sub(1, Ordering::SeqCst);
+        return Err(e);
+    }
+    remove_leaf_dir(&archive.dir, &archive.removable_files, ctx)
+}
+
+/// Writes the archives `compress_images` decided on, in parallel, and removes
+/// the directories they replace. Messages are printed in the order the
+/// directories were processed; the first error is returned once all are done.
+pub fn write_archives(ctx: &Context) -> Result<(), std::io::Error> {
+    let pending = std::mem::take(&mut *ctx.pending_archives.lock().unwrap());
+    let results: Vec<_> = pending
+        .par_iter()
+        .map(|archive| write_archive(archive, ctx))
+        .collect();
+
+    let mut result = Ok(());
+    for (archive, outcome) in pending.iter().zip(results) {
+        match outcome {
+            Ok(kept) => print_kept_dirs(&kept, ctx),
+            Err(e) => {
+                ctx.eprintln(format!(
+                    "Failed to archive directory {}: {}",
+                    archive.dir, e
+                ));
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+    result
+}
+
+pub fn clean_dir(
+    dir: &str,
+    all_files: &[path::PathBuf],
+    ctx: &Context,
+) -> Result<bool, std::io::Error> {
+    ctx.println(format!("Cleaning directory: {}", dir));
+
+    let files: Vec<_> = all_files
+        .iter()
+        .filter(|path| !is_excluded_file(path, ctx))
+        .cloned()
+        .collect();
+
+    let mut deleted_count = 0;
+
+    // Check each file and delete if size is zero
+    for file_path in &files {
+        // Get file metadata to check size
+        match ctx.fs().metadata(file_path) {
+            Ok(metadata) => {
+                // Check if file is zero-sized or hidden (starts with a dot)
+                let is_hidden = file_path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with('.'));
+
+                if metadata.len == 0 || is_hidden {
+                    // File size is zero, delete it
+                    if let Err(e) = ctx.fs().remove_file(file_path) {
+                        ctx.eprintln(format!(
+                            "Failed to delete zero-size file {}: {}",
+                            file_path.display(),
+                            e
+                        ));
+                    } else {
+                        deleted_count += 1;
+                    }
+                }
+            }
+            Err(e) => {
+                ctx.eprintln(format!(
+                    "Failed to get metadata for {}: {}",
+                    file_path.display(),
+                    e
+                ));
+            }
+        }
+    }
+
+    ctx.println(format!(
+        " deleted {} zero-size or hidden files, files {}",
+        deleted_count,
+        files.len()
+    ));
+
+    if deleted_count == files.len() || files.is_empty() {
+        // Excluded files are only deleted when asked for, never by clean mode itself
+        let removable_files = if ctx.delete_excluded {
+            all_files.to_vec()
+        } else {
+            files
+        };
+        if removable_files.len() < all_files.len() {
+            return Ok(true);
+        }
+
+        // If all files were deleted, remove the directory
+        ctx.println(format!("Removing empty directory: {}", dir));
+        match remove_leaf_dir(dir, &removable_files, ctx) {
+            Ok(kept) => print_kept_dirs(&kept, ctx),
+            Err(e) => {
+                ctx.eprintln(format!("Failed to delete directory {}: {}", dir, e));
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indicatif::ProgressDrawTarget;
+    use vfs::MemFs;
+
+    fn context(fs: &MemFs) -> Context {
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        Context::new(Box::new(fs.clone()), multi_progress)
+    }
+
+    fn zip_entries(fs: &MemFs, path: &str) -> Vec<String> {
+        let data = fs.read_file(path).expect("archive should exist");
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect()
+    }
+
+    fn files_in(fs: &MemFs, dir: &str) -> Vec<PathBuf> {
+        let mut files: Vec<_> = fs
+            .read_dir(Path::new(dir))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn compress_archives_image_majority_directories() {
+        let fs = MemFs::new();
+        fs.add_file("lib/images/1.jpg", b"one".to_vec());
+        fs.add_file("lib/images/2.png", b"two".to_vec());
+        fs.add_file("lib/images/notes.txt", b"notes".to_vec());
+        fs.add_file("lib/docs/1.jpg", b"one".to_vec());
+        fs.add_file("lib/docs/a.pdf", b"a".to_vec());
+        fs.add_file("lib/docs/b.pdf", b"b".to_vec());
+        let ctx = context(&fs);
+
+        process_directory("lib", compress_images, &ctx).unwrap();
+
+        assert_eq!(
+            zip_entries(&fs, "lib/images.zip"),
+            vec!["1.jpg", "2.png", "notes.txt"]
+        );
+        assert!(!fs.exists(Path::new("lib/images")));
+        assert!(!fs.exists(Path::new("lib/docs.zip")));
+        assert_eq!(files_in(&fs, "lib/docs").len(), 3);
+        assert!(ctx.skipped_for(SkipReason::NotImageMajority));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_directory_names_are_skipped() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let fs = MemFs::new();
+        let name = OsStr::from_bytes(b"bad\xff");
+        fs.add_file(Path::new("lib").join(name).join("1.jpg"), b"one".to_vec());
+        fs.add_file("lib/good/1.jpg", b"one".to_vec());
+        let ctx = context(&fs);
+
+        process_directory("lib", compress_images, &ctx).unwrap();
+
+        assert!(fs.exists(Path::new("lib/good.zip")));
+        assert!(fs.exists(&Path::new("lib").join(name).join("1.jpg")));
+        assert!(ctx.skipped_for(SkipReason::NonUtf8Name));
+    }
+
+    #[test]
+    fn special_entries_do_not_make_a_directory_a_parent() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a/1.jpg", b"one".to_vec());
+        fs.add_other("lib/a/broken-link");
+        let ctx = context(&fs);
+
+        let files = process_directory("lib", compress_images, &ctx).unwrap();
+
+        assert_eq!(files, vec![PathBuf::from("lib/a/1.jpg")]);
+        assert_eq!(zip_entries(&fs, "lib/a.zip"), vec!["1.jpg"]);
+        assert_eq!(
+            files_in(&fs, "lib/a"),
+            vec![PathBuf::from("lib/a/broken-link")]
+        );
+    }
+
+    #[test]
+    fn no_recurse_archives_the_current_directory() {
+        let mut fs = MemFs::new();
+        fs.set_current_dir("/photos/shoot");
+        fs.add_file("1.jpg", b"one".to_vec());
+        fs.add_file("2.jpg", b"two".to_vec());
+        let mut ctx = context(&fs);
+        ctx.no_recurse = true;
+
+        process_directory(".", compress_images, &ctx).unwrap();
+
+        assert_eq!(
+            zip_entries(&fs, "/photos/shoot.zip"),
+            vec!["1.jpg", "2.jpg"]
+        );
+        assert!(!fs.exists(Path::new("/photos/shoot")));
+    }
+
+    #[test]
+    fn compress_keeps_excluded_files_unless_asked_to_delete_them() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a/1.jpg", b"one".to_vec());
+        fs.add_file("lib/a/info.nfo", b"nfo".to_vec());
+        fs.add_file("lib/b/1.jpg", b"one".to_vec());
+        fs.add_file("lib/b/info.nfo", b"nfo".to_vec());
+
+        let mut ctx = context(&fs);
+        ctx.exclude_ext = vec!["nfo".to_string()];
+        compress_images("lib/a", &files_in(&fs, "lib/a"), &ctx).unwrap();
+        write_archives(&ctx).unwrap();
+        assert_eq!(zip_entries(&fs, "lib/a.zip"), vec!["1.jpg"]);
+        assert_eq!(
+            files_in(&fs, "lib/a"),
+            vec![PathBuf::from("lib/a/info.nfo")]
+        );
+
+        ctx.delete_excluded = true;
+        compress_images("lib/b", &files_in(&fs, "lib/b"), &ctx).unwrap();
+        write_archives(&ctx).unwrap();
+        assert_eq!(zip_entries(&fs, "lib/b.zip"), vec!["1.jpg"]);
+        assert!(!fs.exists(Path::new("lib/b")));
+    }
+
+    #[test]
+    fn compress_adds_a_suffix_when_the_archive_exists() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a.zip", b"old".to_vec());
+        fs.add_file("lib/a(1).zip", b"old".to_vec());
+        fs.add_file("lib/a/1.jpg", b"one".to_vec());
+        let ctx = context(&fs);
+
+        compress_images("lib/a", &files_in(&fs, "lib/a"), &ctx).unwrap();
+        write_archives(&ctx).unwrap();
+
+        assert_eq!(fs.read_file("lib/a.zip"), Some(b"old".to_vec()));
+        assert_eq!(fs.read_file("lib/a(1).zip"), Some(b"old".to_vec()));
+        assert_eq!(zip_entries(&fs, "lib/a(2).zip"), vec!["1.jpg"]);
+    }
+
+    #[test]
+    fn archive_names_follow_the_sorted_order() {
+        // Run a few times: with a parallel traversal the outcome would vary
+        for _ in 0..20 {
+            let fs = MemFs::new();
+            fs.add_file("lib/a.zip", b"old".to_vec());
+            fs.add_file("lib/a/a.jpg", b"a".to_vec());
+            fs.add_file("lib/a(1)/a1.jpg", b"a(1)".to_vec());
+            fs.add_file("lib/b/1.jpg", b"b".to_vec());
+            fs.add_file("lib/c/1.jpg", b"c".to_vec());
+            let mut ctx = context(&fs);
+            ctx.limit = Some(3);
+
+            process_directory("lib", compress_images, &ctx).unwrap();
+
+            assert_eq!(fs.read_file("lib/a.zip"), Some(b"old".to_vec()));
+            assert_eq!(zip_entries(&fs, "lib/a(1).zip"), vec!["a.jpg"]);
+            assert_eq!(zip_entries(&fs, "lib/a(1)(1).zip"), vec!["a1.jpg"]);
+            assert!(fs.exists(Path::new("lib/b.zip")));
+            assert!(!fs.exists(Path::new("lib/c.zip")));
+            assert_eq!(files_in(&fs, "lib/c"), vec![PathBuf::from("lib/c/1.jpg")]);
+            assert!(!fs.exists(Path::new("lib/a")));
+            assert!(!fs.exists(Path::new("lib/a(1)")));
+        }
+    }
+
+    #[test]
+    fn zip_depth_archives_and_removes_whole_subtrees() {
+        let fs = MemFs::new();
+        fs.add_file("lib/series/v1/c1/1.jpg", b"one".to_vec());
+        fs.add_file("lib/series/v1/c2/deep/2.jpg", b"two".to_vec());
+        fs.add_dir("lib/series/v1/c3/empty");
+        fs.add_file("lib/series/top.jpg", b"top".to_vec());
+        fs.add_file("lib/other/v1/1.jpg", b"one".to_vec());
+        let mut ctx = context(&fs);
+        ctx.zip_depth = Some(2);
+
+        process_directory("lib", compress_images, &ctx).unwrap();
+
+        assert_eq!(
+            zip_entries(&fs, "lib/series/v1.zip"),
+            vec!["c1/1.jpg", "c2/deep/2.jpg"]
+        );
+        assert!(!fs.exists(Path::new("lib/series/v1")));
+        assert!(fs.exists(Path::new("lib/series/top.jpg")));
+        assert_eq!(
+            *ctx.skipped.lock().unwrap(),
+            vec![("lib/series".to_string(), SkipReason::AboveZipDepth)]
+        );
+    }
+
+    #[test]
+    fn past_deadline_skips_everything() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a/1.jpg", b"one".to_vec());
+        fs.add_file("lib/b/1.jpg", b"one".to_vec());
+        let mut ctx = context(&fs);
+        ctx.deadline = Some(Instant::now());
+
+        assert!(
+            process_directory("lib", compress_images, &ctx)
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(
+            *ctx.skipped.lock().unwrap(),
+            vec![("lib".to_string(), SkipReason::TimeBudgetExceeded)]
+        );
+        assert_eq!(ctx.archived_count(), 0);
+        assert_eq!(
+            files_in(&fs, "lib"),
+            vec![PathBuf::from("lib/a"), PathBuf::from("lib/b")]
+        );
+    }
+
+    #[test]
+    fn deadline_passed_before_writing_starts_no_archive() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a/1.jpg", b"one".to_vec());
+        let mut ctx = context(&fs);
+
+        compress_images("lib/a", &files_in(&fs, "lib/a"), &ctx).unwrap();
+        ctx.deadline = Some(Instant::now());
+        write_archives(&ctx).unwrap();
+
+        assert!(!fs.exists(Path::new("lib/a.zip")));
+        assert!(!fs.exists(Path::new("lib/a.zip.tmp")));
+        assert_eq!(files_in(&fs, "lib/a"), vec![PathBuf::from("lib/a/1.jpg")]);
+        assert!(ctx.skipped_for(SkipReason::TimeBudgetExceeded));
+        assert_eq!(ctx.archived_count(), 0);
+    }
+
+    #[test]
+    fn strict_skips_directories_with_any_other_file() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a/1.jpg", b"one".to_vec());
+        fs.add_file("lib/a/2.jpg", b"two".to_vec());
+        fs.add_file("lib/a/cover.xmp", b"xmp".to_vec());
+        fs.add_file("lib/b/1.jpg", b"one".to_vec());
+        let mut ctx = context(&fs);
+        ctx.strict = true;
+
+        process_directory("lib", compress_images, &ctx).unwrap();
+
+        assert!(!fs.exists(Path::new("lib/a.zip")));
+        assert_eq!(files_in(&fs, "lib/a").len(), 3);
+        assert!(fs.exists(Path::new("lib/b.zip")));
+        assert_eq!(
+            *ctx.skipped.lock().unwrap(),
+            vec![("lib/a".to_string(), SkipReason::NonImageFiles)]
+        );
+    }
+
+    #[test]
+    fn limit_stops_archiving_after_the_first_directories() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a/1.jpg", b"one".to_vec());
+        fs.add_file("lib/b/1.jpg", b"one".to_vec());
+        let mut ctx = context(&fs);
+        ctx.limit = Some(1);
+
+        process_directory("lib", compress_images, &ctx).unwrap();
+
+        assert!(fs.exists(Path::new("lib/a.zip")));
+        assert!(!fs.exists(Path::new("lib/b.zip")));
+        assert_eq!(files_in(&fs, "lib/b"), vec![PathBuf::from("lib/b/1.jpg")]);
+        assert_eq!(ctx.archived_count(), 1);
+        assert!(ctx.limit_reached());
+        assert_eq!(
+            *ctx.skipped.lock().unwrap(),
+            vec![("lib/b".to_string(), SkipReason::LimitReached)]
+        );
+    }
+
+    #[test]
+    fn top_level_archives_each_child_with_its_subtree() {
+        let fs = MemFs::new();
+        fs.add_file("root/2023/1.jpg", b"one".to_vec());
+        fs.add_file("root/2023/trip/2.jpg", b"two".to_vec());
+        fs.add_file("root/2023/trip/notes.txt", b"notes".to_vec());
+        fs.add_file("root/2024/3.jpg", b"three".to_vec());
+        fs.add_file("root/readme.txt", b"readme".to_vec());
+        let mut ctx = context(&fs);
+        ctx.archive_all = true;
+        ctx.zip_depth = Some(1);
+
+        process_directory("root", compress_images, &ctx).unwrap();
+
+        assert_eq!(
+            zip_entries(&fs, "root/2023.zip"),
+            vec!["1.jpg", "trip/2.jpg", "trip/notes.txt"]
+        );
+        assert_eq!(zip_entries(&fs, "root/2024.zip"), vec!["3.jpg"]);
+        assert_eq!(
+            files_in(&fs, "root"),
+            vec![
+                PathBuf::from("root/2023.zip"),
+                PathBuf::from("root/2024.zip"),
+                PathBuf::from("root/readme.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn pack_archives_directories_without_images() {
+        let fs = MemFs::new();
+        fs.add_file("lib/project/main.psd", b"psd".to_vec());
+        fs.add_file("lib/project/notes.txt", b"notes".to_vec());
+        let mut ctx = context(&fs);
+        ctx.archive_all = true;
+
+        process_directory("lib", compress_images, &ctx).unwrap();
+
+        assert_eq!(
+            zip_entries(&fs, "lib/project.zip"),
+            vec!["main.psd", "notes.txt"]
+        );
+        assert!(!fs.exists(Path::new("lib/project")));
+    }
+
+    #[test]
+    fn single_archive_collects_images_and_keeps_the_sources() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a/1.jpg", b"one".to_vec());
+        fs.add_file("lib/a/notes.txt", b"notes".to_vec());
+        fs.add_file("lib/b/deep/2.png", b"two".to_vec());
+        let ctx = context(&fs);
+        let roots = vec!["lib/a".to_string(), "lib/b".to_string()];
+
+        assert_eq!(create_single_archive("all.zip", &roots, &ctx).unwrap(), 2);
+
+        assert_eq!(zip_entries(&fs, "all.zip"), vec!["a/1.jpg", "b/deep/2.png"]);
+        assert_eq!(files_in(&fs, "lib/a").len(), 2);
+        assert!(fs.exists(Path::new("lib/b/deep/2.png")));
+
+        let err = create_single_archive("all.zip", &roots, &ctx).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn clean_removes_empty_and_hidden_files_only() {
+        let fs = MemFs::new();
+        fs.add_file("lib/junk/empty.jpg", Vec::new());
+        fs.add_file("lib/junk/.DS_Store", b"x".to_vec());
+        fs.add_file("lib/info/release.nfo", b"nfo".to_vec());
+        fs.add_file("lib/info/empty.jpg", Vec::new());
+        fs.add_file("lib/keep/1.jpg", b"one".to_vec());
+        let mut ctx = context(&fs);
+        ctx.exclude_ext = vec!["nfo".to_string()];
+
+        for dir in ["lib/junk", "lib/info", "lib/keep"] {
+            clean_dir(dir, &files_in(&fs, dir), &ctx).unwrap();
+        }
+
+        assert!(!fs.exists(Path::new("lib/junk")));
+        assert_eq!(
+            files_in(&fs, "lib/info"),
+            vec![PathBuf::from("lib/info/release.nfo")]
+        );
+        assert_eq!(
+            files_in(&fs, "lib/keep"),
+            vec![PathBuf::from("lib/keep/1.jpg")]
+        );
+    }
+
+    #[test]
+    fn remove_leaf_dir_keeps_files_it_was_not_given() {
+        let fs = MemFs::new();
+        fs.add_file("lib/a/1.jpg", Vec::new());
+        fs.add_file("lib/a/sub/2.jpg", Vec::new());
+        fs.add_file("lib/a/sub/keep.psd", Vec::new());
+        let ctx = context(&fs);
+
+        let files = vec![
+            PathBuf::from("lib/a/1.jpg"),
+            PathBuf::from("lib/a/sub/2.jpg"),
+        ];
+        remove_leaf_dir("lib/a", &files, &ctx).unwrap();
+
+        assert_eq!(files_in(&fs, "lib/a"), vec![PathBuf::from("lib/a/sub")]);
+        assert_eq!(
+            files_in(&fs, "lib/a/sub"),
+            vec![PathBuf::from("lib/a/sub/keep.psd")]
+        );
+    }
+
+    #[test]
+    fn expand_dir_pattern_matches_relative_roots() {
+        let fs = MemFs::new();
+        fs.add_dir("s1/incoming");
+        fs.add_dir("s2/incoming");
+        fs.add_dir("x3/incoming");
+        fs.add_dir("[Group] Series");
+
+        assert_eq!(
+            expand_dir_pattern(&fs, "./s*/incoming"),
+            Ok(vec![
+                "./s1/incoming".to_string(),
+                "./s2/incoming".to_string()
+            ])
+        );
+        assert_eq!(
+            expand_dir_pattern(&fs, "[Group] Series"),
+            Ok(vec!["[Group] Series".to_string()])
+        );
+        assert!(expand_dir_pattern(&fs, "z*").is_err());
+    }
+
+    fn wildcard(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        matches_wildcard(&pattern, &name)
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("90m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid_input() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("1x").is_err());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("99999999999999999h").is_err());
+        assert!(parse_duration("18446744073709551615s1s").is_err());
+    }
+
+    #[test]
+    fn wildcard_star_and_question_mark() {
+        assert!(wildcard("*", "incoming"));
+        assert!(wildcard("in*ing", "incoming"));
+        assert!(wildcard("vol??", "vol01"));
+        assert!(!wildcard("vol?", "vol01"));
+        assert!(!wildcard("*.zip", "archive.cbz"));
+    }
+
+    #[test]
+    fn wildcard_classes() {
+        assert!(wildcard("s[0-9]", "s7"));
+        assert!(!wildcard("s[0-9]", "sx"));
+        assert!(wildcard("[abc]x", "bx"));
+        assert!(wildcard("[!abc]x", "dx"));
+        assert!(!wildcard("[!abc]x", "ax"));
+    }
+
+    #[test]
+    fn wildcard_escaped_brackets() {
+        assert!(wildcard("[[]Group] *", "[Group] Series"));
+        assert!(!wildcard("[Group] *", "[Group] Series"));
+        assert!(wildcard("[]]", "]"));
+    }
+
+    #[test]
+    fn wildcard_skips_hidden_names() {
+        assert!(!wildcard("*", ".cache"));
+        assert!(wildcard(".*", ".cache"));
+    }
+}
@@ -1,284 +1,181 @@
-use std::path::{self, Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use clap::Parser;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use compress_images::vfs::RealFs;
+use compress_images::{
+    Context, SkipReason, check_if_directory_exists, clean_dir, compress_images,
+    create_single_archive, expand_dir_pattern, parse_duration, process_directory,
+};
+use indicatif::MultiProgress;
 use rayon::ThreadPoolBuilder;
-use rayon::prelude::*;
-use std::fs::File;
-use zip::ZipWriter;
-use zip::write::FileOptions;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
-    dirname: String,
+    /// Directory to process, may contain `*`, `?` and `[...]` wildcards
+    #[arg(short, long, required_unless_present = "dirs")]
+    dirname: Vec<String>,
+    /// More directories to process, same syntax as `--dirname`
+    #[arg(value_name = "DIR")]
+    dirs: Vec<String>,
     #[arg(short, long, default_value_t = 1)]
     num_threads: usize,
     #[arg(short, long)]
     mode: Option<String>,
+    /// Comma-separated file extensions to ignore, e.g. `txt,url,nfo,db`
+    #[arg(long, value_delimiter = ',')]
+    exclude_ext: Vec<String>,
+    /// Delete excluded files together with the directory they are in
+    #[arg(long, requires = "exclude_ext")]
+    delete_excluded: bool,
+    /// Only archive directories whose files are all images, in every mode
+    #[arg(long)]
+    strict: bool,
+    /// Maximum number of directories to archive in this run
+    #[arg(long)]
+    limit: Option<usize>,
+    /// List every skipped directory with the reason it was skipped
+    #[arg(long)]
+    verbose: bool,
+    /// Treat each given directory as a leaf and ignore its subdirectories
+    #[arg(long)]
+    no_recurse: bool,
+    /// Archive every directory at this depth below the root with all its contents
+    #[arg(long, conflicts_with = "no_recurse")]
+    zip_depth: Option<usize>,
+    /// Put every image found below the roots into this one archive instead
+    #[arg(long, value_name = "ZIP", conflicts_with_all = ["mode", "no_recurse", "zip_depth", "limit", "max_duration"])]
+    single_archive: Option<String>,
+    /// Stop starting new directories after this long, e.g. `90m` or `2h`, and
+    /// exit with code 3
+    #[arg(long, value_parser = parse_duration)]
+    max_duration: Option<Duration>,
 }
 
-fn check_if_directory_exists(dir: &str) -> Result<(), String> {
-    let path = path::Path::new(dir);
-    if !path.exists() {
-        return Err(format!("Directory '{}' does not exist", dir));
-    }
-    if !path.is_dir() {
-        return Err(format!("'{}' is not a directory", dir));
-    }
-    Ok(())
-}
+/// Exit code used when `--max-duration` stopped the run before it finished. It
+/// takes precedence over the exit code 1 of failed directories, whose errors
+/// have been printed already.
+const EXIT_TIME_BUDGET_EXCEEDED: i32 = 3;
 
-fn process_directory_recursively<F>(
-    dir: &str,
-    process_leaf_entry_fn: F,
-    multi_progress: &MultiProgress,
-) -> Result<Vec<path::PathBuf>, std::io::Error>
-where
-    F: for<'a> Fn(&'a str, &'a [path::PathBuf], &'a MultiProgress) -> Result<bool, std::io::Error>
-        + Send
-        + Sync
-        + Clone,
-{
-    let dirents: Vec<_> = std::fs::read_dir(dir)?.collect();
-    let (files, dirs): (Vec<_>, Vec<_>) = dirents
-        .into_par_iter()
-        .filter_map(|entry| entry.ok())
-        .partition(|entry| entry.path().is_file());
+fn main() {
+    let args = Args::parse();
+    let num_threads = args.num_threads;
+    let mode = args.mode.unwrap_or_else(|| "compress".to_string());
 
-    let file_paths: Vec<_> = files.iter().map(|e| e.path()).collect();
+    ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build_global()
+        .unwrap();
 
-    if dirs.is_empty() {
-        match process_leaf_entry_fn(dir, &file_paths, multi_progress) {
-            Ok(_) => {
-                return Ok(file_paths);
-            }
+    let mut roots = Vec::new();
+    for pattern in args.dirname.iter().chain(&args.dirs) {
+        match expand_dir_pattern(&RealFs, pattern) {
+            Ok(dirs) => roots.extend(dirs),
             Err(e) => {
-                eprintln!("Error processing directory {}: {}", dir, e);
-                return Err(e);
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
             }
         }
     }
 
-    let subdir_files: Vec<_> = dirs
-        .into_par_iter()
-        .filter_map(|entry| {
-            let path = entry.path();
-            let process_entry = process_leaf_entry_fn.clone();
-            let result = process_directory_recursively(
-                path.to_str().unwrap(),
-                process_entry,
-                multi_progress,
-            )
-            .ok();
-            result
-        })
-        .flatten()
-        .collect();
-
-    Ok(subdir_files)
-}
-
-fn is_image_file(path: &Path) -> bool {
-    if let Some(ext) = path.extension() {
-        let ext = ext.to_string_lossy().to_lowercase();
-        matches!(
-            ext.as_str(),
-            "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tiff" | "avif" | "heic" | "svg"
-        )
-    } else {
-        false
+    for root in &roots {
+        if let Err(e) = check_if_directory_exists(&RealFs, root) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
     }
-}
-
-fn create_zip(
-    output_path: &str,
-    files: &[PathBuf],
-    multi_progress: &MultiProgress,
-) -> Result<(), std::io::Error> {
-    let temp_path = format!("{}.tmp", output_path);
-
-    let file = File::create(&temp_path)?;
-    let mut zip = ZipWriter::new(file);
 
-    let pb = multi_progress.add(ProgressBar::new(files.len() as u64));
-    pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} files ({eta}) {msg}")
-        .unwrap()
-        .progress_chars("#>-"));
-
-    let basename = Path::new(output_path)
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or(output_path);
-    pb.set_message(format!("Zipping: {}", basename));
-
-    let options = FileOptions::<()>::default().compression_method(zip::CompressionMethod::Deflated);
-
-    // Write files to zip from memory
-
-    for path in files {
-        let file_name = path.file_name().and_then(|n| n.to_str()).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid file name")
-        })?;
-        zip.start_file(file_name, options)?;
-        let mut file = File::open(path)?;
-        std::io::copy(&mut file, &mut zip)?;
+    let process_leaf_fn = match mode.as_str() {
+        "compress" | "pack" | "top-level" => compress_images,
+        "clean" => clean_dir,
+        _ => {
+            eprintln!(
+                "Invalid mode: {}. Use 'compress', 'pack', 'top-level' or 'clean'.",
+                mode
+            );
+            std::process::exit(1);
+        }
+    };
 
-        pb.inc(1);
+    // pack and top-level modes archive directories whatever files they contain,
+    // top-level mode archives each immediate child of the root as a whole
+    let archive_all = mode == "pack" || mode == "top-level";
+    let top_level = mode == "top-level";
+    if top_level && (args.zip_depth.is_some() || args.no_recurse) {
+        eprintln!("Error: --mode top-level cannot be combined with --zip-depth or --no-recurse");
+        std::process::exit(1);
     }
+    let zip_depth = if top_level { Some(1) } else { args.zip_depth };
 
-    zip.finish()?;
-    pb.finish_and_clear();
-
-    // Rename the temporary file to the final output path
-    std::fs::rename(temp_path, output_path)?;
-
-    Ok(())
-}
+    // Create a MultiProgress instance to manage multiple progress bars
+    let multi_progress = MultiProgress::new();
 
-fn compress_images(
-    dir: &str,
-    files: &[path::PathBuf],
-    multi_progress: &MultiProgress,
-) -> Result<bool, std::io::Error> {
-    let img_files: Vec<_> = files
+    let mut ctx = Context::new(Box::new(RealFs), multi_progress);
+    ctx.exclude_ext = args
+        .exclude_ext
         .iter()
-        .filter(|path| is_image_file(path))
-        .cloned()
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
         .collect();
-    let other_files: Vec<_> = files
-        .iter()
-        .filter(|path| !is_image_file(path))
-        .cloned()
-        .collect();
-
-    if !files.is_empty() && img_files.len() > other_files.len() {
-        let dir_path = path::Path::new(dir);
-        let dir_name = dir_path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown");
-
-        let parent_dir = dir_path.parent().and_then(|p| p.to_str()).unwrap_or(".");
-        let mut zip_path = format!("{}/{}.zip", parent_dir, dir_name);
-        let mut counter = 1;
-        // Find a non-conflicting path by adding (1), (2), etc. if needed
-        while std::path::Path::new(&zip_path).exists() {
-            zip_path = format!("{}/{}({}).zip", parent_dir, dir_name, counter);
-            counter += 1;
-        }
-
-        if let Err(e) = create_zip(&zip_path, files, multi_progress) {
-            eprintln!("Failed to create zip file: {}", e);
-            return Err(e);
-        }
+    ctx.delete_excluded = args.delete_excluded;
+    ctx.strict = args.strict;
+    ctx.limit = args.limit;
+    ctx.no_recurse = args.no_recurse;
+    ctx.zip_depth = zip_depth;
+    ctx.archive_all = archive_all;
+    ctx.deadline = match args.max_duration {
+        Some(budget) => match Instant::now().checked_add(budget) {
+            Some(deadline) => Some(deadline),
+            None => {
+                eprintln!("Error: --max-duration is too long");
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    // clean mode reports every directory itself
+    if mode != "clean" {
+        ctx.show_scan_progress();
+    }
 
-        // After creating the zip file, delete the original directory
-        match std::fs::remove_dir_all(dir) {
-            Ok(_) => (),
+    if let Some(output_path) = &args.single_archive {
+        match create_single_archive(output_path, &roots, &ctx) {
+            Ok(count) => println!("Archived {} images into {}", count, output_path),
             Err(e) => {
-                eprintln!("Failed to delete directory: {}", e);
-                return Err(e);
+                eprintln!("Failed to create {}: {}", output_path, e);
+                std::process::exit(1);
             }
         }
+        return;
     }
 
-    Ok(true)
-}
-
-fn clean_dir(
-    dir: &str,
-    files: &[path::PathBuf],
-    _multi_progress: &MultiProgress,
-) -> Result<bool, std::io::Error> {
-    println!("Cleaning directory: {}", dir);
-
-    let mut deleted_count = 0;
-
-    // Check each file and delete if size is zero
-    for file_path in files {
-        // Get file metadata to check size
-        match std::fs::metadata(file_path) {
-            Ok(metadata) => {
-                // Check if file is zero-sized or hidden (starts with a dot)
-                let is_hidden = file_path
-                    .file_name()
-                    .and_then(|name| name.to_str())
-                    .is_some_and(|name| name.starts_with('.'));
-
-                if metadata.len() == 0 || is_hidden {
-                    // File size is zero, delete it
-                    if let Err(e) = std::fs::remove_file(file_path) {
-                        eprintln!(
-                            "Failed to delete zero-size file {}: {}",
-                            file_path.display(),
-                            e
-                        );
-                    } else {
-                        deleted_count += 1;
-                    }
-                }
-            }
+    let mut total_files = 0;
+    let mut failed = false;
+    for root in &roots {
+        match process_directory(root, process_leaf_fn, &ctx) {
+            Ok(files) => total_files += files.len(),
             Err(e) => {
-                eprintln!("Failed to get metadata for {}: {}", file_path.display(), e);
+                eprintln!("Failed to process directory {}: {}", root, e);
+                failed = true;
             }
         }
     }
-
-    println!(
-        " deleted {} zero-size or hidden files, files {}",
-        deleted_count,
-        files.len()
-    );
-
-    if deleted_count == files.len() || files.is_empty() {
-        // If all files were deleted, remove the directory
-        println!("Removing empty directory: {}", dir);
-        if let Err(e) = std::fs::remove_dir_all(dir) {
-            eprintln!("Failed to delete directory {}: {}", dir, e);
-            return Err(e);
-        }
+    ctx.finish_scan();
+
+    println!("Total files processed: {}", total_files);
+    ctx.report_skipped(args.verbose);
+    if ctx.limit_reached() {
+        println!(
+            "Reached the --limit of {} archived directories",
+            ctx.archived_count()
+        );
     }
 
-    Ok(true)
-}
-
-fn main() {
-    let args = Args::parse();
-    let num_threads = args.num_threads;
-    let mode = args.mode.unwrap_or_else(|| "compress".to_string());
-
-    ThreadPoolBuilder::new()
-        .num_threads(num_threads)
-        .build_global()
-        .unwrap();
-
-    if let Err(e) = check_if_directory_exists(&args.dirname) {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+    if ctx.skipped_for(SkipReason::TimeBudgetExceeded) {
+        println!("Stopped early: --max-duration exceeded");
+        std::process::exit(EXIT_TIME_BUDGET_EXCEEDED);
     }
-
-    // Create a MultiProgress instance to manage multiple progress bars
-    let multi_progress = MultiProgress::new();
-
-    let process_leaf_fn = match mode.as_str() {
-        "compress" => compress_images,
-        "clean" => clean_dir,
-        _ => {
-            eprintln!("Invalid mode: {}. Use 'compress'.", mode);
-            std::process::exit(1);
-        }
-    };
-
-    match process_directory_recursively(&args.dirname, process_leaf_fn, &multi_progress) {
-        Ok(files) => {
-            println!("Total files processed: {}", files.len());
-        }
-        Err(e) => {
-            eprintln!("Failed to read directory: {}", e);
-            std::process::exit(1);
-        }
+    if failed {
+        std::process::exit(1);
     }
 }
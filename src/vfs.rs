@@ -0,0 +1,480 @@
+//! The filesystem operations used by traversal, archiving and cleaning, so the
+//! pipeline can run against something other than the real filesystem.
+
+use std::collections::BTreeMap;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub path: PathBuf,
+    pub file_type: FileType,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub len: u64,
+}
+
+pub trait WriteSeek: Write + Seek + Send {}
+
+impl<T: Write + Seek + Send> WriteSeek for T {}
+
+pub trait Vfs: Send + Sync {
+    /// Lists the entries of a directory, in no particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+    /// Creates or truncates a file for writing.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WriteSeek>>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Removes a directory, which must be empty.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    /// The directory relative paths are resolved against.
+    fn current_dir(&self) -> io::Result<PathBuf>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// Whether `path` itself is a symlink, without following it.
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+/// The real filesystem, via `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+fn real_file_type(path: &Path) -> FileType {
+    // Follows symlinks, like `Path::is_file` and `Path::is_dir`
+    if path.is_file() {
+        FileType::File
+    } else if path.is_dir() {
+        FileType::Dir
+    } else {
+        FileType::Other
+    }
+}
+
+impl Vfs for RealFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        Ok(std::fs::read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let path = entry.path();
+                let file_type = real_file_type(&path);
+                DirEntry { path, file_type }
+            })
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Metadata {
+            file_type: real_file_type(path),
+            len: metadata.len(),
+        })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(std::fs::File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WriteSeek>> {
+        Ok(Box::new(std::fs::File::create(path)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_dir(path)
+    }
+
+    fn current_dir(&self) -> io::Result<PathBuf> {
+        std::env::current_dir()
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Dir,
+    File(Vec<u8>),
+    Other,
+}
+
+type Nodes = Arc<Mutex<BTreeMap<PathBuf, Node>>>;
+
+/// An in-memory filesystem. Clones share the same contents, so a copy can be
+/// kept to inspect the results after handing one to the pipeline.
+#[derive(Debug, Clone)]
+pub struct MemFs {
+    nodes: Nodes,
+    current_dir: PathBuf,
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        MemFs {
+            nodes: Nodes::default(),
+            current_dir: PathBuf::from("/"),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("'{}' does not exist", path.display()),
+    )
+}
+
+/// The root always exists as a directory.
+fn is_root(path: &Path) -> bool {
+    path.parent().is_none()
+}
+
+fn is_dir(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> bool {
+    is_root(path) || matches!(nodes.get(path), Some(Node::Dir))
+}
+
+fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !is_dir(nodes, parent) => Err(not_found(parent)),
+        _ => Ok(()),
+    }
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `path` absolute and resolves `.` and `..`, so every spelling of a
+    /// path refers to the same entry.
+    fn normalize(&self, path: &Path) -> PathBuf {
+        let mut normalized = PathBuf::new();
+        for component in self.current_dir.join(path).components() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                _ => normalized.push(component),
+            }
+        }
+        normalized
+    }
+
+    /// Changes the directory relative paths are resolved against, creating it
+    /// if needed. Clones made before keep their current directory.
+    pub fn set_current_dir(&mut self, path: impl AsRef<Path>) {
+        self.current_dir = self.normalize(path.as_ref());
+        self.add_dir(self.current_dir.clone());
+    }
+
+    /// Adds a directory, creating missing parents.
+    pub fn add_dir(&self, path: impl AsRef<Path>) {
+        let path = self.normalize(path.as_ref());
+        let mut nodes = self.nodes.lock().unwrap();
+        for ancestor in path.ancestors().filter(|ancestor| !is_root(ancestor)) {
+            nodes.insert(ancestor.to_path_buf(), Node::Dir);
+        }
+    }
+
+    /// Adds a file, creating missing parent directories.
+    pub fn add_file(&self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        let path = self.normalize(path.as_ref());
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        nodes.insert(path, Node::File(contents.into()));
+    }
+
+    /// Adds an entry that is neither a file nor a directory, like a broken
+    /// symlink or a FIFO, creating missing parent directories.
+    pub fn add_other(&self, path: impl AsRef<Path>) {
+        let path = self.normalize(path.as_ref());
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        nodes.insert(path, Node::Other);
+    }
+
+    /// Returns the contents of a file, or `None` if it does not exist.
+    pub fn read_file(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(&self.normalize(path.as_ref())) {
+            Some(Node::File(contents)) => Some(contents.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Vfs for MemFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let key = self.normalize(path);
+        let nodes = self.nodes.lock().unwrap();
+        if !is_dir(&nodes, &key) {
+            return Err(not_found(path));
+        }
+        Ok(nodes
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(key.as_path()))
+            .map(|(child, node)| DirEntry {
+                // Like `std::fs::read_dir`, entries are joined onto the path as given
+                path: path.join(child.file_name().unwrap()),
+                file_type: match node {
+                    Node::Dir => FileType::Dir,
+                    Node::File(_) => FileType::File,
+                    Node::Other => FileType::Other,
+                },
+            })
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = self.normalize(path);
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(&path) {
+            Some(Node::File(contents)) => Ok(Metadata {
+                file_type: FileType::File,
+                len: contents.len() as u64,
+            }),
+            Some(Node::Dir) => Ok(Metadata {
+                file_type: FileType::Dir,
+                len: 0,
+            }),
+            Some(Node::Other) => Ok(Metadata {
+                file_type: FileType::Other,
+                len: 0,
+            }),
+            None if is_root(&path) => Ok(Metadata {
+                file_type: FileType::Dir,
+                len: 0,
+            }),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        match self.read_file(path) {
+            Some(contents) => Ok(Box::new(Cursor::new(contents))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WriteSeek>> {
+        let path = self.normalize(path);
+        let mut nodes = self.nodes.lock().unwrap();
+        check_parent(&nodes, &path)?;
+        if let Some(Node::Dir) = nodes.get(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is a directory", path.display()),
+            ));
+        }
+        nodes.insert(path.clone(), Node::File(Vec::new()));
+        Ok(Box::new(MemWriter {
+            nodes: self.nodes.clone(),
+            path,
+            buffer: Cursor::new(Vec::new()),
+        }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (self.normalize(from), self.normalize(to));
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(&from) {
+            return Err(not_found(&from));
+        }
+        check_parent(&nodes, &to)?;
+
+        // Move the entry together with everything below it
+        let moved: Vec<_> = nodes
+            .keys()
+            .filter(|path| path.starts_with(&from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let node = nodes.remove(&path).unwrap();
+            let relative = path.strip_prefix(&from).unwrap();
+            let target = if relative.as_os_str().is_empty() {
+                to.clone()
+            } else {
+                to.join(relative)
+            };
+            nodes.insert(target, node);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let path = self.normalize(path);
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(&path) {
+            Some(Node::File(_) | Node::Other) => {
+                nodes.remove(&path);
+                Ok(())
+            }
+            Some(Node::Dir) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is a directory", path.display()),
+            )),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        // Like `rmdir`, refuse paths ending in `.` or `..`
+        if path.file_name().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot remove '{}'", path.display()),
+            ));
+        }
+        let path = self.normalize(path);
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(&path) {
+            Some(Node::Dir) => {
+                if nodes
+                    .keys()
+                    .any(|child| child.parent() == Some(path.as_path()))
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::DirectoryNotEmpty,
+                        format!("'{}' is not empty", path.display()),
+                    ));
+                }
+                nodes.remove(&path);
+                Ok(())
+            }
+            Some(Node::File(_) | Node::Other) => Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("'{}' is not a directory", path.display()),
+            )),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn current_dir(&self) -> io::Result<PathBuf> {
+        Ok(self.current_dir.clone())
+    }
+}
+
+/// Buffers writes to a `MemFs` file and stores them on flush and on drop.
+struct MemWriter {
+    nodes: Nodes,
+    path: PathBuf,
+    buffer: Cursor<Vec<u8>>,
+}
+
+impl Write for MemWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        // The file may have been renamed or removed while still open
+        if let Some(Node::File(contents)) = nodes.get_mut(&self.path) {
+            contents.clone_from(self.buffer.get_ref());
+        }
+        Ok(())
+    }
+}
+
+impl Seek for MemWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.buffer.seek(pos)
+    }
+}
+
+impl Drop for MemWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mem_fs_ignores_current_dir_components() {
+        let fs = MemFs::new();
+        fs.add_file("./a/1.jpg", b"one".to_vec());
+
+        assert_eq!(fs.read_file("a/1.jpg"), Some(b"one".to_vec()));
+        assert!(fs.exists(Path::new("./a")));
+        assert!(fs.exists(Path::new("a/./1.jpg")));
+    }
+
+    #[test]
+    fn mem_fs_resolves_paths_against_its_current_dir() {
+        let mut fs = MemFs::new();
+        fs.set_current_dir("/photos/shoot");
+        fs.add_file("1.jpg", b"one".to_vec());
+
+        assert_eq!(fs.current_dir().unwrap(), PathBuf::from("/photos/shoot"));
+        assert_eq!(fs.read_file("/photos/shoot/1.jpg"), Some(b"one".to_vec()));
+        assert!(fs.exists(Path::new("../shoot/1.jpg")));
+        assert!(fs.exists(Path::new(".")));
+    }
+
+    #[test]
+    fn mem_fs_lists_entries_under_the_given_path() {
+        let fs = MemFs::new();
+        fs.add_file("a/1.jpg", Vec::new());
+
+        let root: Vec<_> = fs
+            .read_dir(Path::new("."))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(root, vec![PathBuf::from("./a")]);
+
+        let dir: Vec<_> = fs
+            .read_dir(Path::new("./a"))
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.path, entry.file_type))
+            .collect();
+        assert_eq!(dir, vec![(PathBuf::from("./a/1.jpg"), FileType::File)]);
+    }
+
+    #[test]
+    fn mem_fs_remove_dir_requires_empty_dir() {
+        let fs = MemFs::new();
+        fs.add_file("a/1.jpg", Vec::new());
+
+        assert!(fs.remove_dir(Path::new("a")).is_err());
+        fs.remove_file(Path::new("./a/1.jpg")).unwrap();
+        fs.remove_dir(Path::new("a")).unwrap();
+        assert!(!fs.exists(Path::new("a")));
+    }
+}